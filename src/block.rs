@@ -1,8 +1,23 @@
-use std::{convert::TryInto, marker::PhantomData};
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use sha2::{Digest, Sha256};
 
-static DIFFICULTY: u8 = 16;
+/// Compact ("nBits") encoding of the genesis target: a 2-byte zero prefix followed by
+/// saturated mantissa bytes, roughly equivalent to the crate's old 16-leading-zero-bit
+/// difficulty but expressed as a full `Target`.
+static INITIAL_BITS: u32 = 0x1e_ff_ff_ff;
+
+/// Number of blocks between difficulty retargets, mirroring Bitcoin's 2016-block window.
+static RETARGET_INTERVAL: usize = 16;
+/// Desired number of seconds between blocks; together with `RETARGET_INTERVAL` this
+/// defines the target timespan a retarget window should have taken.
+static TARGET_SECONDS_PER_BLOCK: u64 = 10;
+static TARGET_TIMESPAN: u64 = RETARGET_INTERVAL as u64 * TARGET_SECONDS_PER_BLOCK;
 
 type Hash = [u8; 32];
 
@@ -12,90 +27,432 @@ pub trait Consensus {
     fn is_valid(block: &Block) -> bool;
 }
 
-pub struct ProofOfWork {
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
 
-impl ProofOfWork {
-    fn digest_sha(bytes: &Vec<u8>) -> Hash {
-        let mut hasher = Sha256::new();
-        hasher.update(&bytes);
-        hasher.finalize().try_into().unwrap()
+fn sha256(bytes: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher.finalize().into()
+}
+
+fn merkle_parent(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Builds a Merkle root over `leaves`, the SHA-256 hashes of a block's payloads, by
+/// repeatedly pairing adjacent hashes (duplicating the last one if a level is odd)
+/// until a single root remains. An empty set of leaves yields the all-zero hash.
+fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        level = level.chunks(2).map(|pair| merkle_parent(&pair[0], &pair[1])).collect();
     }
+    level[0]
+}
+
+/// A problem found while decoding a binary-encoded `Block` or `Blockchain`: the byte
+/// stream ran out before a field could be read, a varint was too long, or a decoded
+/// block's proof of work does not check out, so a corrupted file cannot silently
+/// produce an invalid chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    InvalidProofOfWork { index: usize },
+    /// A varint ran past 9 continuation bytes without terminating, which would
+    /// otherwise overflow the `u64` it decodes into.
+    VarintOverflow,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+fn read_slice<'a>(bytes: &'a [u8], cursor: &mut usize, len: usize) -> Result<&'a [u8], DecodeError> {
+    let end = cursor.checked_add(len).ok_or(DecodeError::UnexpectedEof)?;
+    let slice = bytes.get(*cursor..end).ok_or(DecodeError::UnexpectedEof)?;
+    *cursor = end;
+    Ok(slice)
+}
+
+fn read_array<const N: usize>(bytes: &[u8], cursor: &mut usize) -> Result<[u8; N], DecodeError> {
+    read_slice(bytes, cursor, N)?.try_into().map_err(|_| DecodeError::UnexpectedEof)
+}
+
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> Result<u64, DecodeError> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        if shift >= 64 {
+            return Err(DecodeError::VarintOverflow);
+        }
+
+        let byte = read_array::<1>(bytes, cursor)?[0];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    Ok(value)
+}
+
+/// A full 256-bit, big-endian proof-of-work target. A hash is valid proof of work when
+/// it is numerically `<=` the target, exactly as in Bitcoin's SPV check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target([u8; 32]);
+
+impl Target {
+    /// Expands a compact "nBits" encoding (1 exponent byte, 3 mantissa bytes) into a
+    /// full target: `target = mantissa * 256^(exponent - 3)`.
+    pub fn from_compact(bits: u32) -> Self {
+        let exponent = (bits >> 24) as i64;
+        let mantissa = (bits & 0x00ff_ffff).to_be_bytes();
+        let mantissa = [mantissa[1], mantissa[2], mantissa[3]];
+
+        let mut out = [0u8; 32];
+        let start = 32 - exponent;
+        for i in 0..3i64 {
+            let pos = start + i;
+            if pos >= 0 && pos < 32 {
+                out[pos as usize] = mantissa[i as usize];
+            }
+        }
+        Target(out)
+    }
+
+    /// Compresses this target back into its compact "nBits" encoding. Lossy, like
+    /// Bitcoin's own nBits: only the three bytes starting at the most significant
+    /// non-zero byte survive.
+    pub fn to_compact(&self) -> u32 {
+        match self.0.iter().position(|&b| b != 0) {
+            None => 0,
+            Some(pos) => {
+                let exponent = (32 - pos) as u32;
+                let mut mantissa = [0u8; 3];
+                for i in 0..3 {
+                    mantissa[i] = self.0.get(pos + i).copied().unwrap_or(0);
+                }
+                let mantissa = u32::from_be_bytes([0, mantissa[0], mantissa[1], mantissa[2]]);
+                (exponent << 24) | mantissa
+            }
+        }
+    }
+
+    /// Whether `hash`, read as a 256-bit big-endian integer, is `<=` this target.
+    pub fn meets(&self, hash: &Hash) -> bool {
+        hash[..] <= self.0[..]
+    }
+
+    /// Returns `self * numerator / denominator`, saturating at the maximum target
+    /// (all bytes `0xff`) on overflow. Used to retarget difficulty between windows.
+    fn scaled(&self, numerator: u64, denominator: u64) -> Target {
+        let mut le: Vec<u8> = self.0.to_vec();
+        le.reverse();
+
+        let mut carry: u128 = 0;
+        for byte in le.iter_mut() {
+            let product = *byte as u128 * numerator as u128 + carry;
+            *byte = (product & 0xff) as u8;
+            carry = product >> 8;
+        }
+        while carry > 0 {
+            le.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+
+        let mut remainder: u128 = 0;
+        for byte in le.iter_mut().rev() {
+            let acc = (remainder << 8) | *byte as u128;
+            *byte = (acc / denominator as u128) as u8;
+            remainder = acc % denominator as u128;
+        }
+
+        if le[32..].iter().any(|&b| b != 0) {
+            return Target([0xff; 32]);
+        }
 
-    fn prefix(hash: &Hash) -> u32 {
-        u32::from_be_bytes(hash[0..4].try_into().unwrap()) >> (32 - DIFFICULTY)
+        let mut be = [0u8; 32];
+        be.copy_from_slice(&le[..32]);
+        be.reverse();
+        Target(be)
     }
 }
 
+pub struct ProofOfWork {
+}
+
 impl Consensus for ProofOfWork {
     fn to_bytes(block: &Block) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.extend_from_slice(&block.nonce.to_be_bytes());
         bytes.extend_from_slice(&block.previous_hash[..]);
-        bytes.extend_from_slice(&block.payload[..]);
+        bytes.extend_from_slice(&block.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&block.bits.to_be_bytes());
+        bytes.extend_from_slice(&block.extra_nonce.to_be_bytes());
+        bytes.extend_from_slice(&block.merkle_root[..]);
         bytes
     }
 
+    /// Grinds `block.nonce` until its hash meets the target. If the whole 32-bit nonce
+    /// space is exhausted without success, bumps `extra_nonce` (which `to_bytes` also
+    /// commits to) and restarts from nonce zero, rather than wrapping `nonce` silently.
     fn calculate_hash(block: &mut Block) {
 
-        let mut bytes = Self::to_bytes(&block);
+        block.timestamp = now();
+        let target = Target::from_compact(block.bits);
 
         loop {
-            let nonce_bytes = block.nonce.to_be_bytes();
-            bytes[0] = nonce_bytes[0];
-            bytes[1] = nonce_bytes[1];
-            bytes[2] = nonce_bytes[2];
-            bytes[3] = nonce_bytes[3];
-
-            let hash = Self::digest_sha(&bytes);
-            if Self::prefix(&hash) == 0 {
+            let mut bytes = Self::to_bytes(&block);
+
+            loop {
+                let nonce_bytes = block.nonce.to_be_bytes();
+                bytes[0] = nonce_bytes[0];
+                bytes[1] = nonce_bytes[1];
+                bytes[2] = nonce_bytes[2];
+                bytes[3] = nonce_bytes[3];
+
+                let hash = sha256(&bytes);
+                if target.meets(&hash) {
+                    block.hash = hash;
+                    return;
+                }
+
+                match block.nonce.checked_add(1) {
+                    Some(next) => block.nonce = next,
+                    None => break,
+                }
+            }
+
+            block.extra_nonce = block.extra_nonce.wrapping_add(1);
+            block.nonce = 0;
+        }
+    }
+
+    fn is_valid(block: &Block) -> bool {
+        let bytes = Self::to_bytes(&block);
+        let hash = sha256(&bytes);
+        block.hash == hash && Target::from_compact(block.bits).meets(&hash)
+    }
+}
+
+/// Configures the opt-in parallel miner used by `ProofOfWork::calculate_hash_parallel`
+/// and `Blockchain::add_parallel`.
+pub struct MiningConfig {
+    pub threads: usize,
+}
+
+impl MiningConfig {
+    pub fn new(threads: usize) -> Self {
+        MiningConfig { threads: threads.max(1) }
+    }
+}
+
+impl Default for MiningConfig {
+    /// Defaults to one worker thread per available CPU.
+    fn default() -> Self {
+        MiningConfig::new(thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+}
+
+impl ProofOfWork {
+    /// Splits the 32-bit nonce space across `config.threads` worker threads, each
+    /// scanning a disjoint stride (thread `k` of `n` tries nonces `k, k+n, k+2n, ...`),
+    /// sharing a stop flag so the first thread to find a hash meeting the target signals
+    /// the rest to abort. If a whole nonce range is exhausted without success, bumps
+    /// `extra_nonce` (which is itself committed by `to_bytes`) and restarts, rather than
+    /// wrapping `nonce` silently.
+    pub fn calculate_hash_parallel(block: &mut Block, config: &MiningConfig) {
+        loop {
+            block.timestamp = now();
+            let target = Target::from_compact(block.bits);
+            let base_bytes = Self::to_bytes(&block);
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let (sender, receiver) = mpsc::channel();
+            let threads = config.threads as u32;
+
+            thread::scope(|scope| {
+                for worker in 0..threads {
+                    let stop = Arc::clone(&stop);
+                    let sender = sender.clone();
+                    let mut bytes = base_bytes.clone();
+
+                    scope.spawn(move || {
+                        let mut nonce = worker;
+
+                        while !stop.load(Ordering::Relaxed) {
+                            let nonce_bytes = nonce.to_be_bytes();
+                            bytes[0] = nonce_bytes[0];
+                            bytes[1] = nonce_bytes[1];
+                            bytes[2] = nonce_bytes[2];
+                            bytes[3] = nonce_bytes[3];
+
+                            let hash = sha256(&bytes);
+                            if target.meets(&hash) {
+                                stop.store(true, Ordering::Relaxed);
+                                let _ = sender.send((nonce, hash));
+                                return;
+                            }
+
+                            match nonce.checked_add(threads) {
+                                Some(next) => nonce = next,
+                                None => return,
+                            }
+                        }
+                    });
+                }
+            });
+
+            if let Ok((nonce, hash)) = receiver.try_recv() {
+                block.nonce = nonce;
+                block.hash = hash;
+                return;
+            }
+
+            block.extra_nonce = block.extra_nonce.wrapping_add(1);
+        }
+    }
+}
+
+/// Configures a `ProofOfStake` signer: which staker is minting, and how heavily their
+/// stake scales the target (`target * stake_weight`), giving higher-stake stakers a
+/// proportionally larger chance of being eligible in any given slot.
+pub struct StakeConfig {
+    pub staker_id: u64,
+    pub stake_weight: u64,
+}
+
+impl StakeConfig {
+    pub fn new(staker_id: u64, stake_weight: u64) -> Self {
+        StakeConfig { staker_id, stake_weight: stake_weight.max(1) }
+    }
+}
+
+/// A proof-of-stake consensus: rather than grinding a nonce, a block becomes valid once
+/// its configured staker is "elected" for some slot, i.e. `hash(previous_hash || slot ||
+/// staker_id || merkle_root)` falls under the target scaled by that staker's stake
+/// weight. Folding `merkle_root` into the eligibility hash means the hash commits to the
+/// block's payloads, not just its identity, so tampering with a payload after the fact
+/// is caught by `is_valid` like it is for `ProofOfWork`. `slot` and `stake_weight` both
+/// travel with the block so `is_valid` can recheck eligibility without consulting any
+/// external stake ledger.
+pub struct ProofOfStake {
+}
+
+impl Consensus for ProofOfStake {
+    fn to_bytes(block: &Block) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&block.previous_hash[..]);
+        bytes.extend_from_slice(&block.slot.to_be_bytes());
+        bytes.extend_from_slice(&block.staker_id.to_be_bytes());
+        bytes.extend_from_slice(&block.merkle_root[..]);
+        bytes
+    }
+
+    /// Starting from `block.slot`, searches forward for the first slot at which
+    /// `block.staker_id` is eligible, i.e. its hash meets the target scaled by
+    /// `block.stake_weight`.
+    fn calculate_hash(block: &mut Block) {
+
+        block.timestamp = now();
+        let target = Target::from_compact(block.bits).scaled(block.stake_weight.max(1), 1);
+
+        loop {
+            let hash = sha256(&Self::to_bytes(block));
+            if target.meets(&hash) {
                 block.hash = hash;
                 break;
             }
 
-            block.nonce += 1;
+            block.slot += 1;
         }
     }
 
     fn is_valid(block: &Block) -> bool {
         let bytes = Self::to_bytes(&block);
-        let hash = Self::digest_sha(&bytes);
-        block.hash == hash && Self::prefix(&hash) == 0
+        let hash = sha256(&bytes);
+        let target = Target::from_compact(block.bits).scaled(block.stake_weight.max(1), 1);
+        block.hash == hash && target.meets(&hash)
     }
 }
 
+/// One step of a Merkle inclusion proof: the sibling hash at this level, and whether
+/// it sits to the left or the right of the node being proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MerkleProofStep {
+    pub sibling: Hash,
+    pub is_left: bool,
+}
+
 #[derive(Debug)]
 pub struct Block {
     nonce: u32,
     previous_hash: Hash,
-    payload: Vec<u8>,
+    payloads: Vec<Vec<u8>>,
+    merkle_root: Hash,
     hash: Hash,
+    timestamp: u64,
+    bits: u32,
+    extra_nonce: u32,
+    slot: u64,
+    staker_id: u64,
+    stake_weight: u64,
 }
 
 impl Block {
-    
-    fn genesis<T: AsRef<[u8]>>(payload: T) -> Self {
-        Block::new([0; 32], payload)
-    }
 
-    fn chain<T: AsRef<[u8]>>(&self, payload: T) -> Self {
-        Block::new(self.hash, payload)
+    /// Builds a block that commits to many payloads (e.g. transactions) via a Merkle
+    /// root, as real block headers do, rather than a single raw payload.
+    pub fn with_payloads<T: AsRef<[u8]>>(previous_hash: Hash, items: impl IntoIterator<Item = T>, bits: u32) -> Self {
+        Block::raw(previous_hash, items, 0, [0; 32], 0, bits, 0, 0, 0, 0)
     }
 
-    fn new<T: AsRef<[u8]>>(previous_hash: Hash, payload: T) -> Self {
-        Block::raw(previous_hash, payload, 0, [0; 32])
+    /// Builds a block for `ProofOfStake`, starting its slot search at `slot` and
+    /// recording the staker's identity and stake weight so `is_valid` can recheck
+    /// eligibility on its own.
+    pub fn with_staker<T: AsRef<[u8]>>(previous_hash: Hash, items: impl IntoIterator<Item = T>, bits: u32, slot: u64, config: &StakeConfig) -> Self {
+        Block::raw(previous_hash, items, 0, [0; 32], 0, bits, 0, slot, config.staker_id, config.stake_weight)
     }
 
-    pub fn raw<T: AsRef<[u8]>>(previous_hash: Hash, payload: T, nonce: u32, hash: Hash) -> Self {
+    pub fn raw<T: AsRef<[u8]>>(previous_hash: Hash, payloads: impl IntoIterator<Item = T>, nonce: u32, hash: Hash, timestamp: u64, bits: u32, extra_nonce: u32, slot: u64, staker_id: u64, stake_weight: u64) -> Self {
 
-        let mut v = Vec::new();
-        v.extend_from_slice(payload.as_ref());
+        let payloads: Vec<Vec<u8>> = payloads.into_iter().map(|payload| payload.as_ref().to_vec()).collect();
+        let leaves: Vec<Hash> = payloads.iter().map(|payload| sha256(payload)).collect();
 
         Block {
             nonce,
             previous_hash,
-            payload: v,
+            merkle_root: merkle_root(&leaves),
+            payloads,
             hash,
+            timestamp,
+            bits,
+            extra_nonce,
+            slot,
+            staker_id,
+            stake_weight,
         }
     }
 
@@ -103,8 +460,8 @@ impl Block {
         self.previous_hash
     }
 
-    pub fn get_payload(&self) -> Vec<u8> {
-        self.payload.clone()
+    pub fn get_payloads(&self) -> Vec<Vec<u8>> {
+        self.payloads.clone()
     }
 
     pub fn get_nonce(&self) -> u32 {
@@ -114,6 +471,145 @@ impl Block {
     pub fn get_hash(&self) -> Hash {
         self.hash
     }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn get_extra_nonce(&self) -> u32 {
+        self.extra_nonce
+    }
+
+    pub fn get_slot(&self) -> u64 {
+        self.slot
+    }
+
+    pub fn get_staker_id(&self) -> u64 {
+        self.staker_id
+    }
+
+    pub fn get_stake_weight(&self) -> u64 {
+        self.stake_weight
+    }
+
+    pub fn merkle_root(&self) -> Hash {
+        self.merkle_root
+    }
+
+    /// Builds the sibling path proving that `self.get_payloads()[index]` is committed
+    /// to by `merkle_root()`, for use with `verify_inclusion`.
+    pub fn merkle_proof(&self, index: usize) -> Option<Vec<MerkleProofStep>> {
+        if index >= self.payloads.len() {
+            return None;
+        }
+
+        let mut level: Vec<Hash> = self.payloads.iter().map(|payload| sha256(payload)).collect();
+        let mut index = index;
+        let mut proof = Vec::new();
+
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+
+            let sibling_index = index ^ 1;
+            proof.push(MerkleProofStep { sibling: level[sibling_index], is_left: sibling_index < index });
+
+            level = level.chunks(2).map(|pair| merkle_parent(&pair[0], &pair[1])).collect();
+            index /= 2;
+        }
+
+        Some(proof)
+    }
+
+    /// Recomputes the Merkle root from `leaf` and its sibling path, without needing
+    /// the rest of the block's payloads, and checks it matches this block's root.
+    pub fn verify_inclusion(&self, leaf: &[u8], proof: &[MerkleProofStep]) -> bool {
+        let mut current = sha256(leaf);
+
+        for step in proof {
+            current = if step.is_left {
+                merkle_parent(&step.sibling, &current)
+            } else {
+                merkle_parent(&current, &step.sibling)
+            };
+        }
+
+        current == self.merkle_root
+    }
+
+    /// Encodes this block as nonce (4 bytes BE), previous_hash (32 bytes), hash (32
+    /// bytes), timestamp (8 bytes BE), bits (4 bytes BE), extra_nonce (4 bytes BE), slot
+    /// (8 bytes BE), staker_id (8 bytes BE), stake_weight (8 bytes BE), then a varint
+    /// payload count followed by each payload as a varint length and its bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&self.nonce.to_be_bytes());
+        bytes.extend_from_slice(&self.previous_hash[..]);
+        bytes.extend_from_slice(&self.hash[..]);
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&self.bits.to_be_bytes());
+        bytes.extend_from_slice(&self.extra_nonce.to_be_bytes());
+        bytes.extend_from_slice(&self.slot.to_be_bytes());
+        bytes.extend_from_slice(&self.staker_id.to_be_bytes());
+        bytes.extend_from_slice(&self.stake_weight.to_be_bytes());
+
+        write_varint(&mut bytes, self.payloads.len() as u64);
+        for payload in &self.payloads {
+            write_varint(&mut bytes, payload.len() as u64);
+            bytes.extend_from_slice(payload);
+        }
+
+        bytes
+    }
+
+    /// Decodes a block from the front of `bytes`, returning it along with the number
+    /// of bytes consumed so callers can decode a sequence of blocks back to back.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let mut cursor = 0;
+
+        let nonce = u32::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let previous_hash = read_array(bytes, &mut cursor)?;
+        let hash = read_array(bytes, &mut cursor)?;
+        let timestamp = u64::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let bits = u32::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let extra_nonce = u32::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let slot = u64::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let staker_id = u64::from_be_bytes(read_array(bytes, &mut cursor)?);
+        let stake_weight = u64::from_be_bytes(read_array(bytes, &mut cursor)?);
+
+        let payload_count = read_varint(bytes, &mut cursor)?;
+        let mut payloads = Vec::new();
+        for _ in 0..payload_count {
+            let len = read_varint(bytes, &mut cursor)? as usize;
+            payloads.push(read_slice(bytes, &mut cursor, len)?.to_vec());
+        }
+
+        Ok((Block::raw(previous_hash, payloads, nonce, hash, timestamp, bits, extra_nonce, slot, staker_id, stake_weight), cursor))
+    }
+}
+
+/// The kind of problem found while walking a `Blockchain` back to genesis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// The block's hash does not match its payload/nonce, or does not meet its target.
+    BadProofOfWork,
+    /// The block's `previous_hash` does not equal the preceding block's hash.
+    BrokenLink,
+    /// The first block's `previous_hash` is not all-zeros.
+    BadGenesis,
+}
+
+/// Identifies the block at which `Blockchain::validate` found a problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationError {
+    pub index: usize,
+    pub kind: ValidationErrorKind,
 }
 
 pub struct Blockchain<C> {
@@ -131,14 +627,14 @@ impl<C: Consensus> Blockchain<C> {
     }
 
     pub fn add<T: AsRef<[u8]>>(&mut self, payload: T) -> Option<&Block> {
-        let mut block: Block;
+        self.add_payloads(vec![payload])
+    }
 
-        if self.chain.len() == 0 {
-            block = Block::genesis(payload);
-        }
-        else {
-            block = self.chain.last().unwrap().chain(payload);
-        }
+    pub fn add_payloads<T: AsRef<[u8]>>(&mut self, items: impl IntoIterator<Item = T>) -> Option<&Block> {
+        let bits = self.next_bits();
+        let previous_hash = self.chain.last().map(|block| block.hash).unwrap_or([0; 32]);
+
+        let mut block = Block::with_payloads(previous_hash, items, bits);
         C::calculate_hash(&mut block);
 
         self.chain.push(block);
@@ -148,11 +644,167 @@ impl<C: Consensus> Blockchain<C> {
     pub fn iter(&self) -> std::slice::Iter<Block> {
         self.chain.iter()
     }
+
+    /// Walks the chain from genesis to tip, checking that every block's proof of work
+    /// is valid, that each block links to the previous one's hash, and that the first
+    /// block's `previous_hash` is all-zeros. Returns the first problem found, if any.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (index, block) in self.chain.iter().enumerate() {
+            if !C::is_valid(block) {
+                return Err(ValidationError { index, kind: ValidationErrorKind::BadProofOfWork });
+            }
+
+            if index == 0 {
+                if block.previous_hash != [0; 32] {
+                    return Err(ValidationError { index, kind: ValidationErrorKind::BadGenesis });
+                }
+            }
+            else if block.previous_hash != self.chain[index - 1].hash {
+                return Err(ValidationError { index, kind: ValidationErrorKind::BrokenLink });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the whole chain as a varint block count followed by each block's
+    /// `Block::encode`, in order from genesis to tip.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        write_varint(&mut bytes, self.chain.len() as u64);
+        for block in &self.chain {
+            bytes.extend_from_slice(&block.encode());
+        }
+
+        bytes
+    }
+
+    /// Decodes a chain previously produced by `encode`, re-validating every block's
+    /// proof of work so a corrupted file cannot produce an invalid chain silently.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut cursor = 0;
+        let count = read_varint(bytes, &mut cursor)?;
+
+        let mut chain = Vec::new();
+        for index in 0..count as usize {
+            let (block, consumed) = Block::decode(&bytes[cursor..])?;
+            cursor += consumed;
+
+            if !C::is_valid(&block) {
+                return Err(DecodeError::InvalidProofOfWork { index });
+            }
+
+            chain.push(block);
+        }
+
+        Ok(Blockchain { phantom: PhantomData, chain })
+    }
+
+    /// Returns block hashes from the tip back toward genesis with exponentially
+    /// growing gaps, newest first: the first 10 steps back are 1 block apart, then the
+    /// step doubles each time (1, 1, ..., 1, 2, 4, 8, ...) until genesis is reached,
+    /// which is always the final entry. Mirrors Bitcoin's locator used by
+    /// `getblocks`/`getheaders` to let two nodes find their common ancestor cheaply.
+    pub fn locator(&self) -> Vec<Hash> {
+        let mut hashes = Vec::new();
+
+        if self.chain.is_empty() {
+            return hashes;
+        }
+
+        let mut index = self.chain.len() - 1;
+        let mut step = 1usize;
+        let mut steps_taken = 0usize;
+
+        hashes.push(self.chain[index].hash);
+
+        while index > 0 {
+            if steps_taken >= 10 {
+                step *= 2;
+            }
+            index = index.saturating_sub(step);
+            steps_taken += 1;
+            hashes.push(self.chain[index].hash);
+        }
+
+        hashes
+    }
+
+    /// Determines the target (in compact "nBits" form) the next block should be mined
+    /// at: inherited from the tip between retarget boundaries, or recomputed from the
+    /// wall-clock time the last `RETARGET_INTERVAL` blocks took versus `TARGET_TIMESPAN`,
+    /// exactly like Bitcoin's `new_target = old_target * actual / T` retarget.
+    fn next_bits(&self) -> u32 {
+        let height = self.chain.len();
+
+        if height == 0 {
+            return INITIAL_BITS;
+        }
+
+        let tip = &self.chain[height - 1];
+
+        if height % RETARGET_INTERVAL != 0 {
+            return tip.bits;
+        }
+
+        let first = &self.chain[height - RETARGET_INTERVAL];
+        let actual = tip.timestamp.saturating_sub(first.timestamp)
+            .clamp(TARGET_TIMESPAN / 4, TARGET_TIMESPAN * 4);
+
+        Target::from_compact(tip.bits).scaled(actual, TARGET_TIMESPAN).to_compact()
+    }
+}
+
+impl Blockchain<ProofOfWork> {
+
+    /// Like `add`, but mines the block with `ProofOfWork::calculate_hash_parallel`
+    /// using `config`'s worker count instead of a single-threaded search.
+    pub fn add_parallel(&mut self, payload: impl AsRef<[u8]>, config: &MiningConfig) -> Option<&Block> {
+        self.add_payloads_parallel(vec![payload], config)
+    }
+
+    /// Like `add_payloads`, but mines the block with `ProofOfWork::calculate_hash_parallel`
+    /// using `config`'s worker count instead of a single-threaded search.
+    pub fn add_payloads_parallel<T: AsRef<[u8]>>(&mut self, items: impl IntoIterator<Item = T>, config: &MiningConfig) -> Option<&Block> {
+        let bits = self.next_bits();
+        let previous_hash = self.chain.last().map(|block| block.hash).unwrap_or([0; 32]);
+
+        let mut block = Block::with_payloads(previous_hash, items, bits);
+        ProofOfWork::calculate_hash_parallel(&mut block, config);
+
+        self.chain.push(block);
+        self.chain.last()
+    }
+}
+
+impl Blockchain<ProofOfStake> {
+
+    /// Like `add`, but mints the block for the staker described by `config` instead of
+    /// grinding a nonce.
+    pub fn add_staked(&mut self, payload: impl AsRef<[u8]>, config: &StakeConfig) -> Option<&Block> {
+        self.add_payloads_staked(vec![payload], config)
+    }
+
+    /// Like `add_payloads`, but mints the block for the staker described by `config`
+    /// instead of grinding a nonce. The slot search starts one past the tip's slot, so
+    /// slots strictly increase down the chain.
+    pub fn add_payloads_staked<T: AsRef<[u8]>>(&mut self, items: impl IntoIterator<Item = T>, config: &StakeConfig) -> Option<&Block> {
+        let bits = self.next_bits();
+        let previous_hash = self.chain.last().map(|block| block.hash).unwrap_or([0; 32]);
+        let slot = self.chain.last().map(|block| block.slot + 1).unwrap_or(0);
+
+        let mut block = Block::with_staker(previous_hash, items, bits, slot, config);
+        ProofOfStake::calculate_hash(&mut block);
+
+        self.chain.push(block);
+        self.chain.last()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::block::{Blockchain, ProofOfWork};
+    use crate::block::{Block, Blockchain, Consensus, DecodeError, Hash, MiningConfig, ProofOfStake, ProofOfWork, StakeConfig, Target, ValidationError, ValidationErrorKind, INITIAL_BITS, RETARGET_INTERVAL};
 
     #[test]
     fn blockchain_pow() {
@@ -164,26 +816,274 @@ mod tests {
         chain.add(vec![0]);
         chain.add(vec![0]);
 
-        let mut iter = chain.iter();
+        let mut previous_hash = [0; 32];
+
+        for block in chain.iter() {
+            assert!(ProofOfWork::is_valid(block));
+            assert_eq!(block.get_previous_hash(), previous_hash);
+            assert_eq!(block.get_bits(), INITIAL_BITS);
+            previous_hash = block.get_hash();
+        }
+    }
+
+    #[test]
+    fn bits_inherited_between_retargets() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        for _ in 0..RETARGET_INTERVAL {
+            chain.add(vec![0]);
+        }
+
+        let bits: Vec<u32> = chain.iter().map(|block| block.get_bits()).collect();
+        assert!(bits.iter().all(|&b| b == bits[0]));
+    }
+
+    #[test]
+    fn compact_target_round_trips() {
+
+        let bits = 0x1e_ff_ff_ff;
+        assert_eq!(Target::from_compact(bits).to_compact(), bits);
+    }
+
+    #[test]
+    fn validate_accepts_a_well_formed_chain() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+        chain.add("testing");
+        chain.add([1, 2, 3]);
+
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_a_broken_link() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+        chain.add("testing");
+        chain.add([1, 2, 3]);
+
+        // Each remaining block still carries valid proof of work; only the middle
+        // block's previous_hash link is now missing from the chain.
+        chain.chain.remove(1);
+
+        assert_eq!(chain.validate(), Err(ValidationError { index: 1, kind: ValidationErrorKind::BrokenLink }));
+    }
+
+    #[test]
+    fn validate_rejects_a_bad_genesis() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+        chain.add("testing");
+
+        // The second block has valid proof of work but a non-zero previous_hash, so
+        // dropping the true genesis block leaves an invalid genesis at index 0.
+        chain.chain.remove(0);
+
+        assert_eq!(chain.validate(), Err(ValidationError { index: 0, kind: ValidationErrorKind::BadGenesis }));
+    }
+
+    #[test]
+    fn merkle_proof_verifies_inclusion_of_each_payload() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add_payloads(vec!["a", "b", "c"]);
+
+        let block = chain.iter().next().unwrap();
+
+        for (index, payload) in block.get_payloads().iter().enumerate() {
+            let proof = block.merkle_proof(index).unwrap();
+            assert!(block.verify_inclusion(payload, &proof));
+        }
+    }
+
+    #[test]
+    fn merkle_proof_rejects_a_payload_not_in_the_block() {
 
-        let mut block = iter.next().unwrap();
-        assert_eq!(block.get_nonce(), 66693);
-        assert_eq!(block.get_hash(), [0x00, 0x00, 0xf6, 0xa4, 0x4e, 0x5a, 0x00, 0xf8, 0x67, 0x6d, 0x62, 0xcc, 0x0d, 0xdd, 0x66, 0xee, 0x57, 0x03, 0x94, 0xc4, 0x53, 0x8e, 0x2b, 0x1c, 0xf0, 0xb7, 0xbd, 0x36, 0x2c, 0x06, 0xab, 0x75]);
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add_payloads(vec!["a", "b", "c"]);
+
+        let block = chain.iter().next().unwrap();
+        let proof = block.merkle_proof(0).unwrap();
+
+        assert!(!block.verify_inclusion(b"not in the block", &proof));
+    }
+
+    #[test]
+    fn encode_decode_round_trips_a_chain() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+        chain.add_payloads(vec!["a", "b", "c"]);
+        chain.add("testing");
+
+        let decoded = Blockchain::<ProofOfWork>::decode(&chain.encode()).unwrap();
+
+        let original: Vec<Hash> = chain.iter().map(|block| block.get_hash()).collect();
+        let roundtripped: Vec<Hash> = decoded.iter().map(|block| block.get_hash()).collect();
+        assert_eq!(original, roundtripped);
+    }
+
+    #[test]
+    fn decode_rejects_truncated_bytes() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+
+        let mut bytes = chain.encode();
+        bytes.truncate(bytes.len() - 1);
+
+        match Blockchain::<ProofOfWork>::decode(&bytes) {
+            Err(error) => assert_eq!(error, DecodeError::UnexpectedEof),
+            Ok(_) => panic!("expected decode to fail"),
+        }
+    }
+
+    #[test]
+    fn decode_rejects_a_varint_with_too_many_continuation_bytes() {
+
+        let bytes = [0xff; 12];
+
+        match Blockchain::<ProofOfWork>::decode(&bytes) {
+            Err(error) => assert_eq!(error, DecodeError::VarintOverflow),
+            Ok(_) => panic!("expected decode to fail"),
+        }
+    }
+
+    #[test]
+    fn decode_rejects_a_corrupted_block() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+
+        let mut bytes = chain.encode();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        match Blockchain::<ProofOfWork>::decode(&bytes) {
+            Err(error) => assert_eq!(error, DecodeError::InvalidProofOfWork { index: 0 }),
+            Ok(_) => panic!("expected decode to fail"),
+        }
+    }
+
+    #[test]
+    fn locator_starts_at_the_tip_and_ends_at_genesis() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        for i in 0..25 {
+            chain.add(vec![i]);
+        }
+
+        let blocks: Vec<Hash> = chain.iter().map(|block| block.get_hash()).collect();
+        let locator = chain.locator();
+
+        assert_eq!(locator.first(), blocks.last());
+        assert_eq!(locator.last(), blocks.first());
+
+        // The first 10 steps back from the tip are 1 block apart.
+        assert_eq!(&locator[..11], &blocks[14..25].iter().rev().copied().collect::<Vec<Hash>>()[..]);
+    }
+
+    #[test]
+    fn locator_on_a_short_chain_is_just_genesis() {
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add(vec![0]);
+
+        let genesis_hash = chain.iter().next().unwrap().get_hash();
+        assert_eq!(chain.locator(), vec![genesis_hash]);
+    }
+
+    #[test]
+    fn add_parallel_produces_a_valid_chain() {
+
+        let config = MiningConfig::new(4);
+
+        let mut chain = Blockchain::<ProofOfWork>::new();
+        chain.add_parallel(vec![0], &config);
+        chain.add_payloads_parallel(vec!["a", "b"], &config);
+
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn add_parallel_with_a_single_thread_mines_at_the_same_bits_as_single_threaded_mining() {
+
+        let mut single_threaded = Blockchain::<ProofOfWork>::new();
+        single_threaded.add(vec![0]);
+
+        let mut parallel = Blockchain::<ProofOfWork>::new();
+        parallel.add_parallel(vec![0], &MiningConfig::new(1));
+
+        assert_eq!(parallel.validate(), Ok(()));
+        assert_eq!(single_threaded.iter().next().unwrap().get_bits(), parallel.iter().next().unwrap().get_bits());
+    }
+
+    #[test]
+    fn blockchain_pos() {
+
+        let config = StakeConfig::new(1, 10);
+
+        let mut chain = Blockchain::<ProofOfStake>::new();
+        chain.add_staked(vec![0], &config);
+        chain.add_staked("testing", &config);
+
+        let mut previous_hash = [0; 32];
+
+        for block in chain.iter() {
+            assert!(ProofOfStake::is_valid(block));
+            assert_eq!(block.get_previous_hash(), previous_hash);
+            assert_eq!(block.get_staker_id(), config.staker_id);
+            assert_eq!(block.get_stake_weight(), config.stake_weight);
+            previous_hash = block.get_hash();
+        }
+    }
+
+    #[test]
+    fn pos_slots_strictly_increase_down_the_chain() {
+
+        let config = StakeConfig::new(1, 10);
+
+        let mut chain = Blockchain::<ProofOfStake>::new();
+        chain.add_staked(vec![0], &config);
+        chain.add_staked(vec![0], &config);
+        chain.add_staked(vec![0], &config);
+
+        let slots: Vec<u64> = chain.iter().map(|block| block.get_slot()).collect();
+        assert!(slots.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn pos_rejects_a_block_whose_staker_id_was_tampered_with() {
+
+        let config = StakeConfig::new(1, 10);
+
+        let mut chain = Blockchain::<ProofOfStake>::new();
+        chain.add_staked(vec![0], &config);
+
+        let tampered = {
+            let block = chain.iter().next().unwrap();
+            Block::raw(block.get_previous_hash(), block.get_payloads(), block.get_nonce(), block.get_hash(), block.get_timestamp(), block.get_bits(), block.get_extra_nonce(), block.get_slot(), block.get_staker_id() + 1, block.get_stake_weight())
+        };
+
+        assert!(!ProofOfStake::is_valid(&tampered));
+    }
+
+    #[test]
+    fn pos_rejects_a_block_whose_payload_was_tampered_with() {
 
-        block = iter.next().unwrap();
-        assert_eq!(block.get_nonce(), 6392);
-        assert_eq!(block.get_hash(), [0x00, 0x00, 0xde, 0x34, 0xf5, 0x9e, 0x84, 0xef, 0x95, 0x15, 0xa7, 0xe4, 0x08, 0xc1, 0x3f, 0x30, 0x5c, 0xed, 0x4d, 0xfd, 0xa4, 0x44, 0x22, 0xd6, 0x66, 0x86, 0x2c, 0x2b, 0x5d, 0xc2, 0x09, 0x82]);
+        let config = StakeConfig::new(1, 10);
 
-        block = iter.next().unwrap();
-        assert_eq!(block.get_nonce(), 67878);
-        assert_eq!(block.get_hash(), [0x00, 0x00, 0xdf, 0x0a, 0x46, 0x85, 0x53, 0xf0, 0xd9, 0x6e, 0xf3, 0xda, 0x40, 0x08, 0x6b, 0xd9, 0x1b, 0xbc, 0xb8, 0xcd, 0x5b, 0x8a, 0xa3, 0xee, 0xb0, 0x4a, 0xb3, 0x19, 0xfb, 0xae, 0x24, 0x29]);
+        let mut chain = Blockchain::<ProofOfStake>::new();
+        chain.add_staked(vec![0], &config);
 
-        block = iter.next().unwrap();
-        assert_eq!(block.get_nonce(), 6064);
-        assert_eq!(block.get_hash(), [0x00, 0x00, 0x69, 0xaf, 0x4e, 0xfa, 0xb7, 0xfb, 0x0a, 0x31, 0xf2, 0x2a, 0x5b, 0x46, 0xd7, 0xfb, 0x37, 0x3a, 0xe8, 0x08, 0xfc, 0x04, 0x6b, 0x24, 0x98, 0xd0, 0xf2, 0x05, 0x72, 0xa4, 0x8f, 0x99]);
+        let tampered = {
+            let block = chain.iter().next().unwrap();
+            Block::raw(block.get_previous_hash(), vec![vec![9, 9, 9]], block.get_nonce(), block.get_hash(), block.get_timestamp(), block.get_bits(), block.get_extra_nonce(), block.get_slot(), block.get_staker_id(), block.get_stake_weight())
+        };
 
-        block = iter.next().unwrap();
-        assert_eq!(block.get_nonce(), 80666);
-        assert_eq!(block.get_hash(), [0x00, 0x00, 0x3d, 0x3c, 0x35, 0x07, 0xb3, 0x9f, 0xcd, 0x8b, 0xdb, 0xf5, 0x09, 0xd0, 0x40, 0x1c, 0x61, 0x8c, 0xe9, 0x8f, 0xf3, 0x1f, 0x60, 0xd6, 0xe0, 0x34, 0x35, 0xb2, 0xb7, 0x91, 0x6b, 0xee]);
+        assert!(!ProofOfStake::is_valid(&tampered));
     }
 }
\ No newline at end of file